@@ -0,0 +1,381 @@
+//! Persistent metadata index for the archive.
+//!
+//! `get_dicom_tree` used to walk `/home/gacquewi/dicom` and `open_file`
+//! every instance on every request, which doesn't scale past a few
+//! hundred studies. Instead we scan once - at startup, or on a manual
+//! `/api/reindex` - and keep the patient/study/series hierarchy (plus a
+//! flat per-instance record used for search) in memory behind
+//! `web::Data`, persisted to a JSON cache file between runs.
+
+use actix_web::{web, HttpResponse, Result};
+use dicom::object::open_file;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+pub const ARCHIVE_ROOT: &str = "/home/gacquewi/dicom";
+const CACHE_PATH: &str = "dicom_index_cache.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DicomSeriesInfo {
+    pub series_uid: String,
+    pub series_description: String,
+    pub modality: String,
+    pub image_count: usize,
+    pub images: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DicomStudyInfo {
+    pub study_uid: String,
+    pub study_description: String,
+    pub study_date: String,
+    pub series: HashMap<String, DicomSeriesInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DicomPatientInfo {
+    pub patient_id: String,
+    pub patient_name: String,
+    pub studies: HashMap<String, DicomStudyInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct DicomTreeResponse {
+    pub patients: HashMap<String, DicomPatientInfo>,
+}
+
+/// Flat, per-instance record kept alongside the nested tree so
+/// `/api/search` doesn't have to walk the hierarchy for every query.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IndexedInstance {
+    pub path: String,
+    pub study_uid: String,
+    pub patient_id: String,
+    pub patient_name: String,
+    pub study_description: String,
+    pub study_date: String,
+    pub modality: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct DicomIndex {
+    pub tree: DicomTreeResponse,
+    pub instances: Vec<IndexedInstance>,
+}
+
+/// Shared, lock-protected index handed around the app as `web::Data`.
+pub struct IndexState(pub RwLock<DicomIndex>);
+
+/// Shared by [`crate::dicomdir`] as well. Trims defensively: CS/LO-style
+/// values are space-padded to even length by DICOM, and a record type
+/// like `"PATIENT"` left padded would silently fail every `match` arm
+/// that compares against the untrimmed string.
+pub(crate) fn tag_str(obj: &dicom::object::InMemDicomObject, name: &str, default: &str) -> String {
+    obj.element_by_name(name)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| default.to_string())
+}
+
+/// Walks the archive once, building the nested tree and the flat
+/// instance list in a single pass. Prefers a top-level `DICOMDIR`, when
+/// present, over the full recursive scan - see [`crate::dicomdir`].
+pub fn build_index(base_path: &Path) -> DicomIndex {
+    if let Some(index) = crate::dicomdir::try_build_index(base_path) {
+        return index;
+    }
+
+    let mut patients: HashMap<String, DicomPatientInfo> = HashMap::new();
+    let mut instances = Vec::new();
+
+    fn scan_directory(
+        dir: &Path,
+        base_path: &Path,
+        patients: &mut HashMap<String, DicomPatientInfo>,
+        instances: &mut Vec<IndexedInstance>,
+    ) {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+
+                if path.is_dir() {
+                    scan_directory(&path, base_path, patients, instances);
+                } else if let Ok(obj) = open_file(&path) {
+                    let patient_id = tag_str(&obj, "PatientID", "UNKNOWN");
+                    let patient_name = tag_str(&obj, "PatientName", "Unknown");
+                    let study_uid = tag_str(&obj, "StudyInstanceUID", "UNKNOWN_STUDY");
+                    let study_description = tag_str(&obj, "StudyDescription", "");
+                    let study_date = tag_str(&obj, "StudyDate", "Unknown");
+                    let series_uid = tag_str(&obj, "SeriesInstanceUID", "UNKNOWN_SERIES");
+                    let series_description = tag_str(&obj, "SeriesDescription", "");
+                    let modality = tag_str(&obj, "Modality", "Unknown");
+
+                    let relative_path = path
+                        .strip_prefix(base_path)
+                        .unwrap_or(&path)
+                        .to_string_lossy()
+                        .to_string();
+
+                    let patient = patients.entry(patient_id.clone()).or_insert(DicomPatientInfo {
+                        patient_id: patient_id.clone(),
+                        patient_name: patient_name.clone(),
+                        studies: HashMap::new(),
+                    });
+
+                    let study = patient.studies.entry(study_uid.clone()).or_insert(DicomStudyInfo {
+                        study_uid: study_uid.clone(),
+                        study_description: study_description.clone(),
+                        study_date: study_date.clone(),
+                        series: HashMap::new(),
+                    });
+
+                    let series = study.series.entry(series_uid.clone()).or_insert(DicomSeriesInfo {
+                        series_uid: series_uid.clone(),
+                        series_description,
+                        modality: modality.clone(),
+                        image_count: 0,
+                        images: Vec::new(),
+                    });
+
+                    series.images.push(relative_path.clone());
+                    series.image_count = series.images.len();
+
+                    instances.push(IndexedInstance {
+                        path: relative_path,
+                        study_uid,
+                        patient_id,
+                        patient_name,
+                        study_description,
+                        study_date,
+                        modality,
+                    });
+                }
+            }
+        }
+    }
+
+    scan_directory(base_path, base_path, &mut patients, &mut instances);
+
+    DicomIndex { tree: DicomTreeResponse { patients }, instances }
+}
+
+pub fn load_from_disk(cache_path: &Path) -> Option<DicomIndex> {
+    let file = File::open(cache_path).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+fn save_to_disk(index: &DicomIndex, cache_path: &Path) {
+    match File::create(cache_path) {
+        Ok(file) => {
+            if let Err(e) = serde_json::to_writer(BufWriter::new(file), index) {
+                eprintln!("Warning: failed to persist DICOM index cache: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Warning: failed to create DICOM index cache file: {}", e),
+    }
+}
+
+/// Builds a fresh index (from the archive, not the disk cache) and
+/// persists it, for use both at startup and from `/api/reindex`.
+pub fn rebuild_and_persist() -> DicomIndex {
+    let index = build_index(&PathBuf::from(ARCHIVE_ROOT));
+    save_to_disk(&index, &PathBuf::from(CACHE_PATH));
+    index
+}
+
+/// Loads the on-disk cache if present, otherwise scans the archive and
+/// writes a fresh cache. Called once at startup.
+pub fn load_or_build() -> DicomIndex {
+    match load_from_disk(&PathBuf::from(CACHE_PATH)) {
+        Some(index) => index,
+        None => rebuild_and_persist(),
+    }
+}
+
+/// Looks up a series by UID across the whole tree and returns its image
+/// paths, for the series-level frame-list endpoint.
+pub fn images_for_series(index: &DicomIndex, series_uid: &str) -> Option<Vec<String>> {
+    for patient in index.tree.patients.values() {
+        for study in patient.studies.values() {
+            if let Some(series) = study.series.get(series_uid) {
+                return Some(series.images.clone());
+            }
+        }
+    }
+    None
+}
+
+/// Looks up a study by UID, for DICOMweb handlers that need to read the
+/// cached hierarchy instead of re-walking the archive.
+pub fn find_study<'a>(index: &'a DicomIndex, study_uid: &str) -> Option<(&'a DicomPatientInfo, &'a DicomStudyInfo)> {
+    index.tree.patients.values().find_map(|patient| {
+        patient.studies.get(study_uid).map(|study| (patient, study))
+    })
+}
+
+/// Looks up a series within a specific study by UID.
+pub fn find_series<'a>(index: &'a DicomIndex, study_uid: &str, series_uid: &str) -> Option<&'a DicomSeriesInfo> {
+    find_study(index, study_uid).and_then(|(_, study)| study.series.get(series_uid))
+}
+
+/// `GET /api/tree` - serves the cached hierarchy instead of rescanning.
+pub async fn get_tree(state: web::Data<IndexState>) -> Result<HttpResponse> {
+    let index = state.0.read().unwrap();
+    Ok(HttpResponse::Ok().json(&index.tree))
+}
+
+/// `POST /api/reindex` - rescans the archive and refreshes the cache.
+///
+/// `rebuild_and_persist` walks the whole archive and `open_file`s every
+/// instance, so it runs on a blocking-pool thread via `web::block` rather
+/// than directly on the async worker thread.
+pub async fn reindex(state: web::Data<IndexState>) -> Result<HttpResponse> {
+    let fresh = web::block(rebuild_and_persist)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let patient_count = fresh.tree.patients.len();
+    let instance_count = fresh.instances.len();
+
+    *state.0.write().unwrap() = fresh;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "patients": patient_count,
+        "instances": instance_count,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+}
+
+#[derive(Serialize)]
+pub struct SearchResult {
+    study_uid: String,
+    patient_name: String,
+    patient_id: String,
+    study_description: String,
+    study_date: String,
+    modality: String,
+    score: u32,
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.to_lowercase().split_whitespace().map(|s| s.to_string()).collect()
+}
+
+/// Scores a study's tokens against the query terms: an exact token match
+/// beats a prefix match, and every matching term adds up.
+fn score_study(study_tokens: &[String], query_terms: &[String]) -> u32 {
+    let mut score = 0;
+    for term in query_terms {
+        for token in study_tokens {
+            if token == term {
+                score += 3;
+            } else if token.starts_with(term.as_str()) {
+                score += 1;
+            }
+        }
+    }
+    score
+}
+
+/// `GET /api/search?q=` - tokenized, case-insensitive, prefix-matching
+/// search over PatientName, PatientID, StudyDescription, Modality and
+/// StudyDate, grouped by study and ranked by score.
+pub async fn search(state: web::Data<IndexState>, query: web::Query<SearchQuery>) -> Result<HttpResponse> {
+    let query_terms = tokenize(query.q.trim());
+    if query_terms.is_empty() {
+        return Ok(HttpResponse::Ok().json(Vec::<SearchResult>::new()));
+    }
+
+    let index = state.0.read().unwrap();
+
+    // A study's token set is per-study, not per-file: if every instance's
+    // tokens were appended, a study with many images would score far
+    // higher than one with few regardless of relevance. Dedup with a set
+    // before scoring so image count doesn't factor in.
+    let mut by_study: HashMap<String, (HashSet<String>, SearchResult)> = HashMap::new();
+    for inst in &index.instances {
+        let (tokens, _) = by_study.entry(inst.study_uid.clone()).or_insert_with(|| {
+            (
+                HashSet::new(),
+                SearchResult {
+                    study_uid: inst.study_uid.clone(),
+                    patient_name: inst.patient_name.clone(),
+                    patient_id: inst.patient_id.clone(),
+                    study_description: inst.study_description.clone(),
+                    study_date: inst.study_date.clone(),
+                    modality: inst.modality.clone(),
+                    score: 0,
+                },
+            )
+        });
+
+        tokens.extend(tokenize(&inst.patient_name));
+        tokens.extend(tokenize(&inst.patient_id));
+        tokens.extend(tokenize(&inst.study_description));
+        tokens.extend(tokenize(&inst.modality));
+        tokens.extend(tokenize(&inst.study_date));
+    }
+
+    let mut results: Vec<SearchResult> = by_study
+        .into_values()
+        .map(|(tokens, mut result)| {
+            let tokens: Vec<String> = tokens.into_iter().collect();
+            result.score = score_study(&tokens, &query_terms);
+            result
+        })
+        .filter(|r| r.score > 0)
+        .collect();
+
+    results.sort_by(|a, b| b.score.cmp(&a.score));
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn score_study_exact_match_beats_prefix_match() {
+        let exact = score_study(&tokens(&["ct"]), &tokens(&["ct"]));
+        let prefix = score_study(&tokens(&["ctscan"]), &tokens(&["ct"]));
+        assert!(exact > prefix);
+    }
+
+    #[test]
+    fn score_study_sums_across_terms() {
+        let study_tokens = tokens(&["john", "doe", "ct", "chest"]);
+        let one_term = score_study(&study_tokens, &tokens(&["ct"]));
+        let two_terms = score_study(&study_tokens, &tokens(&["ct", "chest"]));
+        assert!(two_terms > one_term);
+    }
+
+    #[test]
+    fn score_study_counts_once_per_token_present() {
+        // score_study scores whatever token slice it's handed; it's
+        // search()'s job to dedupe per study before calling it (so a
+        // study with many images doesn't outscore one with few purely
+        // from having more files) - this just pins the one-token, one-hit
+        // behavior search() relies on that invariant for.
+        let study_tokens = tokens(&["john", "doe", "ct"]);
+        assert_eq!(score_study(&study_tokens, &tokens(&["john"])), 3);
+    }
+
+    #[test]
+    fn score_study_no_match_scores_zero() {
+        assert_eq!(score_study(&tokens(&["ct", "chest"]), &tokens(&["mri"])), 0);
+    }
+}
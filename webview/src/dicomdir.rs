@@ -0,0 +1,165 @@
+//! DICOMDIR parsing.
+//!
+//! Media exports (CDs, PACS dumps) often ship a top-level `DICOMDIR` file
+//! whose `DirectoryRecordSequence` already enumerates the patient -> study
+//! -> series -> image hierarchy, so [`crate::dicom_index::build_index`]
+//! prefers it over a full recursive scan when one is present.
+
+use crate::dicom_index::{
+    tag_str, DicomIndex, DicomPatientInfo, DicomSeriesInfo, DicomStudyInfo, DicomTreeResponse, IndexedInstance,
+};
+use dicom::object::{open_file, InMemDicomObject};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const DICOMDIR_FILENAME: &str = "DICOMDIR";
+
+/// Rebuilds a backslash-separated DICOM component list (e.g.
+/// `"DICOM\0001\0001"`) into an OS path, since it's a multi-valued
+/// component list rather than an OS path and assuming `\` or `/` would
+/// be wrong depending on platform.
+fn path_from_backslash_components(raw: &str) -> String {
+    let mut path = PathBuf::new();
+    for component in raw.split('\\').filter(|c| !c.is_empty()) {
+        path.push(component);
+    }
+    path.to_string_lossy().to_string()
+}
+
+fn referenced_relative_path(record: &InMemDicomObject) -> Option<String> {
+    let raw = record.element_by_name("ReferencedFileID").ok()?.to_str().ok()?;
+    Some(path_from_backslash_components(&raw))
+}
+
+/// Walks `DirectoryRecordSequence`, rebuilding the patient/study/series
+/// hierarchy from the PATIENT/STUDY/SERIES/IMAGE directory records.
+///
+/// This assumes records appear in depth-first order, which every encoder
+/// we've run into does in practice; a fully spec-compliant reader would
+/// instead follow `OffsetOfReferencedLowerLevelDirectoryEntity` pointers.
+fn parse_dicomdir(dicomdir_path: &Path) -> Option<DicomIndex> {
+    let dicomdir = open_file(dicomdir_path).ok()?;
+    let records = dicomdir.element_by_name("DirectoryRecordSequence").ok()?.items()?;
+
+    let mut patients: HashMap<String, DicomPatientInfo> = HashMap::new();
+    let mut instances = Vec::new();
+
+    let (mut patient_id, mut patient_name) = (String::new(), String::new());
+    let (mut study_uid, mut study_description, mut study_date) = (String::new(), String::new(), String::new());
+    let (mut series_uid, mut series_description, mut modality) = (String::new(), String::new(), String::new());
+
+    for record in records {
+        match tag_str(record, "DirectoryRecordType", "").as_str() {
+            "PATIENT" => {
+                patient_id = tag_str(record, "PatientID", "UNKNOWN");
+                patient_name = tag_str(record, "PatientName", "Unknown");
+                patients.entry(patient_id.clone()).or_insert_with(|| DicomPatientInfo {
+                    patient_id: patient_id.clone(),
+                    patient_name: patient_name.clone(),
+                    studies: HashMap::new(),
+                });
+            }
+            "STUDY" => {
+                study_uid = tag_str(record, "StudyInstanceUID", "UNKNOWN_STUDY");
+                study_description = tag_str(record, "StudyDescription", "");
+                study_date = tag_str(record, "StudyDate", "Unknown");
+                if let Some(patient) = patients.get_mut(&patient_id) {
+                    patient.studies.entry(study_uid.clone()).or_insert_with(|| DicomStudyInfo {
+                        study_uid: study_uid.clone(),
+                        study_description: study_description.clone(),
+                        study_date: study_date.clone(),
+                        series: HashMap::new(),
+                    });
+                }
+            }
+            "SERIES" => {
+                series_uid = tag_str(record, "SeriesInstanceUID", "UNKNOWN_SERIES");
+                series_description = tag_str(record, "SeriesDescription", "");
+                modality = tag_str(record, "Modality", "Unknown");
+                if let Some(study) = patients.get_mut(&patient_id).and_then(|p| p.studies.get_mut(&study_uid)) {
+                    study.series.entry(series_uid.clone()).or_insert_with(|| DicomSeriesInfo {
+                        series_uid: series_uid.clone(),
+                        series_description: series_description.clone(),
+                        modality: modality.clone(),
+                        image_count: 0,
+                        images: Vec::new(),
+                    });
+                }
+            }
+            "IMAGE" => {
+                let Some(relative_path) = referenced_relative_path(record) else {
+                    continue;
+                };
+
+                if let Some(series) = patients
+                    .get_mut(&patient_id)
+                    .and_then(|p| p.studies.get_mut(&study_uid))
+                    .and_then(|s| s.series.get_mut(&series_uid))
+                {
+                    series.images.push(relative_path.clone());
+                    series.image_count = series.images.len();
+                }
+
+                instances.push(IndexedInstance {
+                    path: relative_path,
+                    study_uid: study_uid.clone(),
+                    patient_id: patient_id.clone(),
+                    patient_name: patient_name.clone(),
+                    study_description: study_description.clone(),
+                    study_date: study_date.clone(),
+                    modality: modality.clone(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Some(DicomIndex { tree: DicomTreeResponse { patients }, instances })
+}
+
+/// Builds the index from `{base_path}/DICOMDIR` if it exists and parses
+/// cleanly, returning `None` so the caller can fall back to a full scan.
+pub fn try_build_index(base_path: &Path) -> Option<DicomIndex> {
+    let dicomdir_path = base_path.join(DICOMDIR_FILENAME);
+    if !dicomdir_path.is_file() {
+        return None;
+    }
+
+    match parse_dicomdir(&dicomdir_path) {
+        // A DICOMDIR that parsed but yielded no patients is indistinguishable
+        // from one whose DirectoryRecordType values never matched our match
+        // arms (e.g. due to untrimmed padding) - treat it the same as a
+        // parse failure rather than caching an empty tree.
+        Some(index) if !index.tree.patients.is_empty() => Some(index),
+        _ => {
+            eprintln!("Warning: found a DICOMDIR but failed to parse it, falling back to a full scan");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebuilds_backslash_components_with_os_separator() {
+        let rebuilt = path_from_backslash_components("DICOM\\0001\\0001");
+        let expected = PathBuf::from("DICOM").join("0001").join("0001");
+        assert_eq!(rebuilt, expected.to_string_lossy().to_string());
+    }
+
+    #[test]
+    fn ignores_empty_components() {
+        // A leading/trailing `\` (or a doubled one) shouldn't produce an
+        // empty path segment.
+        let rebuilt = path_from_backslash_components("\\DICOM\\\\0001\\");
+        let expected = PathBuf::from("DICOM").join("0001");
+        assert_eq!(rebuilt, expected.to_string_lossy().to_string());
+    }
+
+    #[test]
+    fn single_component_is_passed_through() {
+        assert_eq!(path_from_backslash_components("IMAGE001"), "IMAGE001");
+    }
+}
@@ -0,0 +1,395 @@
+//! Standards-compliant DICOMweb endpoints (QIDO-RS / WADO-RS).
+//!
+//! These sit alongside the bespoke `/api/*` routes and let off-the-shelf
+//! viewers (OHIF, Cornerstone, ...) talk to this server without any
+//! client-side changes. Responses follow the DICOM JSON Model (PS3.18
+//! Annex F): each attribute is keyed by its tag in uppercase hex
+//! (`"GGGGEEEE"`) and carries a `vr` plus a `Value` array.
+//!
+//! Study/series listings are served from the shared [`crate::dicom_index`]
+//! instead of rescanning the archive, the same way `/api/tree` is. Only
+//! the handful of tags the index doesn't carry (StudyTime, AccessionNumber,
+//! SeriesNumber, SOPInstanceUID, InstanceNumber, pixel geometry, ...) still
+//! require opening files: one representative file per study or series for
+//! the study/series listings, and the files within the matched series for
+//! instance-level requests.
+
+use crate::dicom_index;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use dicom::object::{open_file, InMemDicomObject};
+use serde_json::{json, Map, Value};
+use std::path::PathBuf;
+
+const DICOM_JSON: &str = "application/dicom+json";
+
+fn str_tag(obj: &InMemDicomObject, name: &str) -> Option<String> {
+    obj.element_by_name(name)
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn int_tag(obj: &InMemDicomObject, name: &str) -> Option<i64> {
+    obj.element_by_name(name).ok().and_then(|e| e.to_int::<i64>().ok())
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.is_empty() { None } else { Some(s.to_string()) }
+}
+
+/// Builds one DICOM JSON attribute entry, e.g. `{"vr": "CS", "Value": ["CT"]}`.
+fn entry(vr: &str, value: Value) -> Value {
+    json!({ "vr": vr, "Value": [value] })
+}
+
+fn entry_str(vr: &str, value: &str) -> Value {
+    entry(vr, Value::String(value.to_string()))
+}
+
+fn person_name_entry(name: &str) -> Value {
+    json!({ "vr": "PN", "Value": [{ "Alphabetic": name }] })
+}
+
+fn set_str(map: &mut Map<String, Value>, tag: &str, vr: &str, value: Option<String>) {
+    if let Some(v) = value {
+        map.insert(tag.to_string(), entry_str(vr, &v));
+    }
+}
+
+fn set_int(map: &mut Map<String, Value>, tag: &str, vr: &str, value: Option<i64>) {
+    if let Some(v) = value {
+        map.insert(tag.to_string(), entry(vr, json!(v)));
+    }
+}
+
+/// Renders the patient + study level attributes QIDO-RS study queries
+/// return. `modalities` is the distinct set of modalities across the
+/// study's series: the first becomes `Modality` (0008,0060) and the
+/// full set becomes `ModalitiesInStudy` (0008,0061) - these are two
+/// different attributes and neither substitutes for the other.
+fn build_patient_study_fields(
+    patient_id: &str,
+    patient_name: &str,
+    study_uid: &str,
+    study_description: &str,
+    study_date: &str,
+    study_time: Option<String>,
+    accession_number: Option<String>,
+    modalities: &[String],
+) -> Map<String, Value> {
+    let mut map = Map::new();
+    if let Some(name) = non_empty(patient_name) {
+        map.insert("00100010".to_string(), person_name_entry(&name));
+    }
+    set_str(&mut map, "00100020", "LO", non_empty(patient_id));
+    set_str(&mut map, "0020000D", "UI", non_empty(study_uid));
+    set_str(&mut map, "00081030", "LO", non_empty(study_description));
+    set_str(&mut map, "00080020", "DA", non_empty(study_date));
+    set_str(&mut map, "00080030", "TM", study_time);
+    set_str(&mut map, "00080050", "SH", accession_number);
+    if let Some(first) = modalities.first() {
+        set_str(&mut map, "00080060", "CS", Some(first.clone()));
+    }
+    if !modalities.is_empty() {
+        map.insert("00080061".to_string(), json!({ "vr": "CS", "Value": modalities }));
+    }
+    map
+}
+
+fn build_series_fields(series_uid: &str, series_description: &str, modality: &str, series_number: Option<i64>) -> Map<String, Value> {
+    let mut map = Map::new();
+    set_str(&mut map, "0020000E", "UI", non_empty(series_uid));
+    set_str(&mut map, "0008103E", "LO", non_empty(series_description));
+    set_str(&mut map, "00080060", "CS", non_empty(modality));
+    set_int(&mut map, "00200011", "IS", series_number);
+    map
+}
+
+fn series_fields_from_object(obj: &InMemDicomObject) -> Map<String, Value> {
+    build_series_fields(
+        &str_tag(obj, "SeriesInstanceUID").unwrap_or_default(),
+        &str_tag(obj, "SeriesDescription").unwrap_or_default(),
+        &str_tag(obj, "Modality").unwrap_or_default(),
+        int_tag(obj, "SeriesNumber"),
+    )
+}
+
+fn instance_fields_from_object(obj: &InMemDicomObject) -> Map<String, Value> {
+    let mut map = Map::new();
+    set_str(&mut map, "00080018", "UI", str_tag(obj, "SOPInstanceUID"));
+    set_str(&mut map, "00080016", "UI", str_tag(obj, "SOPClassUID"));
+    set_int(&mut map, "00200013", "IS", int_tag(obj, "InstanceNumber"));
+    set_int(&mut map, "00280010", "US", int_tag(obj, "Rows"));
+    set_int(&mut map, "00280011", "US", int_tag(obj, "Columns"));
+    set_int(&mut map, "00280008", "IS", int_tag(obj, "NumberOfFrames"));
+    map
+}
+
+/// Full metadata (WADO-RS `.../metadata`) combines all three levels plus
+/// pixel-related attributes, since a single instance is self-describing.
+/// Unlike the tree-driven study listing, there's no series set to
+/// aggregate here, so only the plain `Modality` is reported.
+fn metadata_json(obj: &InMemDicomObject) -> Map<String, Value> {
+    let mut map = build_patient_study_fields(
+        &str_tag(obj, "PatientID").unwrap_or_default(),
+        &str_tag(obj, "PatientName").unwrap_or_default(),
+        &str_tag(obj, "StudyInstanceUID").unwrap_or_default(),
+        &str_tag(obj, "StudyDescription").unwrap_or_default(),
+        &str_tag(obj, "StudyDate").unwrap_or_default(),
+        str_tag(obj, "StudyTime"),
+        str_tag(obj, "AccessionNumber"),
+        &[],
+    );
+    map.extend(series_fields_from_object(obj));
+    map.extend(instance_fields_from_object(obj));
+    set_str(&mut map, "00280004", "CS", str_tag(obj, "PhotometricInterpretation"));
+    set_int(&mut map, "00280100", "US", int_tag(obj, "BitsAllocated"));
+    map
+}
+
+fn split_media_type(media_type: &str) -> (&str, &str) {
+    let mut parts = media_type.splitn(2, '/');
+    let media_type = parts.next().unwrap_or("").trim();
+    let subtype = parts.next().unwrap_or("").trim();
+    (media_type, subtype)
+}
+
+/// Matches a concrete media type against a pattern that may use `*` for
+/// either component (`"*/*"`, `"image/*"`, `"image/jpeg"`), so a client
+/// sending `Accept: image/jpeg` matches an offered `"image/*"`.
+fn media_type_matches(candidate: &str, pattern: &str) -> bool {
+    let (candidate_type, candidate_subtype) = split_media_type(candidate);
+    let (pattern_type, pattern_subtype) = split_media_type(pattern);
+
+    (pattern_type == "*" || pattern_type.eq_ignore_ascii_case(candidate_type))
+        && (pattern_subtype == "*" || pattern_subtype.eq_ignore_ascii_case(candidate_subtype))
+}
+
+/// Returns `true` when the request's `Accept` header matches one of
+/// `acceptable` (or is absent, which DICOMweb clients rarely do but we
+/// don't want to punish).
+fn accepts(req: &HttpRequest, acceptable: &[&str]) -> bool {
+    let header = match req.headers().get("Accept").and_then(|v| v.to_str().ok()) {
+        Some(h) => h,
+        None => return true,
+    };
+
+    header.split(',').any(|part| {
+        let media_type = part.split(';').next().unwrap_or("").trim();
+        acceptable.iter().any(|a| media_type_matches(media_type, a))
+    })
+}
+
+fn not_acceptable() -> HttpResponse {
+    HttpResponse::NotAcceptable().json("No representation available for the requested Accept type")
+}
+
+/// QIDO-RS: `GET /studies`
+pub async fn search_studies(req: HttpRequest, state: web::Data<dicom_index::IndexState>) -> Result<HttpResponse> {
+    if !accepts(&req, &[DICOM_JSON, "application/json"]) {
+        return Ok(not_acceptable());
+    }
+
+    let base_path = PathBuf::from(dicom_index::ARCHIVE_ROOT);
+    let index = state.0.read().unwrap();
+    let mut studies = Vec::new();
+
+    for patient in index.tree.patients.values() {
+        for study in patient.studies.values() {
+            let mut modalities: Vec<String> = study.series.values()
+                .map(|series| series.modality.clone())
+                .filter(|m| !m.is_empty())
+                .collect();
+            modalities.sort();
+            modalities.dedup();
+
+            // StudyTime/AccessionNumber aren't carried by the index, so
+            // pull them from one representative instance of the study.
+            let extra = study.series.values()
+                .filter_map(|series| series.images.first())
+                .next()
+                .and_then(|path| open_file(base_path.join(path)).ok());
+
+            studies.push(build_patient_study_fields(
+                &patient.patient_id,
+                &patient.patient_name,
+                &study.study_uid,
+                &study.study_description,
+                &study.study_date,
+                extra.as_ref().and_then(|o| str_tag(o, "StudyTime")),
+                extra.as_ref().and_then(|o| str_tag(o, "AccessionNumber")),
+                &modalities,
+            ));
+        }
+    }
+
+    Ok(HttpResponse::Ok().content_type(DICOM_JSON).json(studies))
+}
+
+/// QIDO-RS: `GET /studies/{study}/series`
+pub async fn search_series(
+    req: HttpRequest,
+    path: web::Path<String>,
+    state: web::Data<dicom_index::IndexState>,
+) -> Result<HttpResponse> {
+    if !accepts(&req, &[DICOM_JSON, "application/json"]) {
+        return Ok(not_acceptable());
+    }
+
+    let study_uid = path.into_inner();
+    let base_path = PathBuf::from(dicom_index::ARCHIVE_ROOT);
+    let index = state.0.read().unwrap();
+
+    let Some((_, study)) = dicom_index::find_study(&index, &study_uid) else {
+        return Ok(HttpResponse::Ok().content_type(DICOM_JSON).json(Vec::<Map<String, Value>>::new()));
+    };
+
+    let mut series_list = Vec::new();
+    for series in study.series.values() {
+        // SeriesNumber isn't carried by the index either.
+        let series_number = series.images.first()
+            .and_then(|path| open_file(base_path.join(path)).ok())
+            .and_then(|obj| int_tag(&obj, "SeriesNumber"));
+
+        series_list.push(build_series_fields(&series.series_uid, &series.series_description, &series.modality, series_number));
+    }
+
+    Ok(HttpResponse::Ok().content_type(DICOM_JSON).json(series_list))
+}
+
+/// QIDO-RS: `GET /studies/{study}/series/{series}/instances`
+pub async fn search_instances(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    state: web::Data<dicom_index::IndexState>,
+) -> Result<HttpResponse> {
+    if !accepts(&req, &[DICOM_JSON, "application/json"]) {
+        return Ok(not_acceptable());
+    }
+
+    let (study_uid, series_uid) = path.into_inner();
+    let base_path = PathBuf::from(dicom_index::ARCHIVE_ROOT);
+
+    let images = {
+        let index = state.0.read().unwrap();
+        dicom_index::find_series(&index, &study_uid, &series_uid).map(|series| series.images.clone())
+    };
+
+    let mut instances = Vec::new();
+    if let Some(images) = images {
+        for relative_path in images {
+            if let Ok(obj) = open_file(base_path.join(&relative_path)) {
+                instances.push(instance_fields_from_object(&obj));
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().content_type(DICOM_JSON).json(instances))
+}
+
+/// WADO-RS: `GET /studies/{study}/series/{series}/instances/{instance}/metadata`
+pub async fn retrieve_metadata(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    state: web::Data<dicom_index::IndexState>,
+) -> Result<HttpResponse> {
+    if !accepts(&req, &[DICOM_JSON, "application/json"]) {
+        return Ok(not_acceptable());
+    }
+
+    let (study_uid, series_uid, sop_instance_uid) = path.into_inner();
+    let base_path = PathBuf::from(dicom_index::ARCHIVE_ROOT);
+
+    let images = {
+        let index = state.0.read().unwrap();
+        dicom_index::find_series(&index, &study_uid, &series_uid).map(|series| series.images.clone())
+    };
+
+    let Some(images) = images else {
+        return Ok(HttpResponse::NotFound().json("Series not found"));
+    };
+
+    for relative_path in images {
+        let Ok(obj) = open_file(base_path.join(&relative_path)) else { continue };
+        if str_tag(&obj, "SOPInstanceUID").as_deref() == Some(sop_instance_uid.as_str()) {
+            return Ok(HttpResponse::Ok().content_type(DICOM_JSON).json(vec![metadata_json(&obj)]));
+        }
+    }
+
+    Ok(HttpResponse::NotFound().json("Instance not found"))
+}
+
+/// WADO-RS: `GET /studies/{study}/series/{series}/instances/{instance}/frames/{n}`
+///
+/// Real WADO-RS wraps frame payloads in `multipart/related`; until the
+/// frontend needs that we just return the requested frame's decoded
+/// pixel bytes as `application/octet-stream`. `read_frame_bytes` always
+/// decodes via `decode_pixel_data`, so the bytes are raw samples, not
+/// the instance's original (possibly compressed) transfer syntax - the
+/// content type reflects that rather than claiming to match it.
+pub async fn retrieve_frame(
+    req: HttpRequest,
+    path: web::Path<(String, String, String, u32)>,
+    state: web::Data<dicom_index::IndexState>,
+) -> Result<HttpResponse> {
+    if !accepts(&req, &["application/octet-stream", "image/*", "*/*"]) {
+        return Ok(not_acceptable());
+    }
+
+    let (study_uid, series_uid, sop_instance_uid, frame) = path.into_inner();
+    let base_path = PathBuf::from(dicom_index::ARCHIVE_ROOT);
+
+    let images = {
+        let index = state.0.read().unwrap();
+        dicom_index::find_series(&index, &study_uid, &series_uid).map(|series| series.images.clone())
+    };
+
+    let Some(images) = images else {
+        return Ok(HttpResponse::NotFound().json("Series not found"));
+    };
+
+    for relative_path in images {
+        let file_path = base_path.join(&relative_path);
+        let Ok(obj) = open_file(&file_path) else { continue };
+        if str_tag(&obj, "SOPInstanceUID").as_deref() != Some(sop_instance_uid.as_str()) {
+            continue;
+        }
+
+        return match crate::read_frame_bytes(&file_path, frame.saturating_sub(1)) {
+            Ok(bytes) => Ok(HttpResponse::Ok().content_type("application/octet-stream").body(bytes)),
+            Err(e) => Ok(HttpResponse::BadRequest().json(e)),
+        };
+    }
+
+    Ok(HttpResponse::NotFound().json("Instance not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn media_type_matches_exact() {
+        assert!(media_type_matches("application/dicom+json", "application/dicom+json"));
+        assert!(!media_type_matches("application/json", "application/dicom+json"));
+    }
+
+    #[test]
+    fn media_type_matches_subtype_wildcard() {
+        assert!(media_type_matches("image/jpeg", "image/*"));
+        assert!(media_type_matches("image/png", "image/*"));
+        assert!(!media_type_matches("application/json", "image/*"));
+    }
+
+    #[test]
+    fn media_type_matches_full_wildcard() {
+        assert!(media_type_matches("anything/at-all", "*/*"));
+    }
+
+    #[test]
+    fn media_type_matches_is_case_insensitive() {
+        assert!(media_type_matches("Image/JPEG", "image/*"));
+    }
+}
@@ -1,11 +1,16 @@
 use actix_web::{web, App, HttpResponse, HttpServer, Result};
 use actix_files as fs;
 use actix_cors::Cors;
-use serde::Serialize;
-use std::path::PathBuf;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use dicom::object::open_file;
 use dicom::pixeldata::PixelDecoder;
+use image::{ImageFormat, RgbImage};
+use std::io::Cursor;
+
+mod dicom_index;
+mod dicomdir;
+mod dicomweb;
 
 #[derive(Serialize)]
 struct FileEntry {
@@ -14,35 +19,6 @@ struct FileEntry {
     is_dir: bool,
 }
 
-#[derive(Serialize, Clone)]
-struct DicomSeriesInfo {
-    series_uid: String,
-    series_description: String,
-    modality: String,
-    image_count: usize,
-    images: Vec<String>,
-}
-
-#[derive(Serialize, Clone)]
-struct DicomStudyInfo {
-    study_uid: String,
-    study_description: String,
-    study_date: String,
-    series: HashMap<String, DicomSeriesInfo>,
-}
-
-#[derive(Serialize)]
-struct DicomPatientInfo {
-    patient_id: String,
-    patient_name: String,
-    studies: HashMap<String, DicomStudyInfo>,
-}
-
-#[derive(Serialize)]
-struct DicomTreeResponse {
-    patients: HashMap<String, DicomPatientInfo>,
-}
-
 #[derive(Serialize)]
 struct DicomInfo {
     patient_name: String,
@@ -53,6 +29,7 @@ struct DicomInfo {
     bits_allocated: u32,
     bits_stored: u32,
     photometric_interpretation: String,
+    number_of_frames: u32,
 }
 
 #[derive(Serialize)]
@@ -62,102 +39,35 @@ struct DicomPixelData {
     data: Vec<u8>,
 }
 
-async fn get_dicom_tree() -> Result<HttpResponse> {
-    let base_path = PathBuf::from("/home/gacquewi/dicom");
-    let mut patients: HashMap<String, DicomPatientInfo> = HashMap::new();
-    
-    fn scan_directory(dir: &PathBuf, patients: &mut HashMap<String, DicomPatientInfo>) {
-        if let Ok(entries) = std::fs::read_dir(dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                
-                if path.is_dir() {
-                    scan_directory(&path, patients);
-                } else if let Ok(obj) = open_file(&path) {
-                    let patient_id = obj.element_by_name("PatientID")
-                        .ok()
-                        .and_then(|e| e.to_str().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "UNKNOWN".to_string());
-                    
-                    let patient_name = obj.element_by_name("PatientName")
-                        .ok()
-                        .and_then(|e| e.to_str().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "Unknown".to_string());
-                    
-                    let study_uid = obj.element_by_name("StudyInstanceUID")
-                        .ok()
-                        .and_then(|e| e.to_str().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "UNKNOWN_STUDY".to_string());
-                    
-                    let study_description = obj.element_by_name("StudyDescription")
-                        .ok()
-                        .and_then(|e| e.to_str().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "".to_string());
-                    
-                    let study_date = obj.element_by_name("StudyDate")
-                        .ok()
-                        .and_then(|e| e.to_str().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "Unknown".to_string());
-                    
-                    let series_uid = obj.element_by_name("SeriesInstanceUID")
-                        .ok()
-                        .and_then(|e| e.to_str().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "UNKNOWN_SERIES".to_string());
-                    
-                    let series_description = obj.element_by_name("SeriesDescription")
-                        .ok()
-                        .and_then(|e| e.to_str().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "".to_string());
-                    
-                    let modality = obj.element_by_name("Modality")
-                        .ok()
-                        .and_then(|e| e.to_str().ok())
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "Unknown".to_string());
-                    
-                    let relative_path = path.strip_prefix("/home/gacquewi/dicom")
-                        .unwrap_or(&path)
-                        .to_string_lossy()
-                        .to_string();
-                    
-                    let patient = patients.entry(patient_id.clone()).or_insert(DicomPatientInfo {
-                        patient_id: patient_id.clone(),
-                        patient_name: patient_name.clone(),
-                        studies: HashMap::new(),
-                    });
-                    
-                    let study = patient.studies.entry(study_uid.clone()).or_insert(DicomStudyInfo {
-                        study_uid: study_uid.clone(),
-                        study_description,
-                        study_date,
-                        series: HashMap::new(),
-                    });
-                    
-                    let series = study.series.entry(series_uid.clone()).or_insert(DicomSeriesInfo {
-                        series_uid: series_uid.clone(),
-                        series_description,
-                        modality,
-                        image_count: 0,
-                        images: Vec::new(),
-                    });
-                    
-                    series.images.push(relative_path);
-                    series.image_count = series.images.len();
-                }
-            }
-        }
-    }
-    
-    scan_directory(&base_path, &mut patients);
-    
-    Ok(HttpResponse::Ok().json(DicomTreeResponse { patients }))
+#[derive(Deserialize)]
+struct WindowQuery {
+    wc: Option<f64>,
+    ww: Option<f64>,
+    format: Option<String>,
+}
+
+/// Parses the first component of a (possibly backslash-multivalued) DICOM
+/// decimal string, e.g. `"40\\400"` -> `40.0`.
+fn parse_first_f64(raw: &str) -> Option<f64> {
+    raw.split('\\').next()?.trim().parse::<f64>().ok()
+}
+
+/// Maps one modality-LUT-corrected pixel value to an 8-bit gray level
+/// through the VOI window, per DICOM PS3.3 C.11.2.1.2. `width <= 1.0` is
+/// a degenerate window (and also guards against the division below),
+/// so it's treated as a hard threshold at `center` instead.
+fn apply_voi_window(value: f64, center: f64, width: f64, invert: bool) -> u8 {
+    let windowed = if width <= 1.0 {
+        if value < center { 0.0 } else { 255.0 }
+    } else {
+        (((value - (center - 0.5)) / (width - 1.0) + 0.5) * 255.0).clamp(0.0, 255.0)
+    };
+
+    (if invert { 255.0 - windowed } else { windowed }).round() as u8
+}
+
+async fn get_dicom_tree(state: web::Data<dicom_index::IndexState>) -> Result<HttpResponse> {
+    dicom_index::get_tree(state).await
 }
 
 async fn list_files(path: web::Path<String>) -> Result<HttpResponse> {
@@ -260,6 +170,11 @@ async fn get_dicom_info(path: web::Path<String>) -> Result<HttpResponse> {
                 .map(|s| s.to_string())
                 .unwrap_or_else(|| "MONOCHROME2".to_string());
 
+            let number_of_frames = obj.element_by_name("NumberOfFrames")
+                .ok()
+                .and_then(|e| e.to_int::<u32>().ok())
+                .unwrap_or(1);
+
             Ok(HttpResponse::Ok().json(DicomInfo {
                 patient_name,
                 study_date,
@@ -269,121 +184,324 @@ async fn get_dicom_info(path: web::Path<String>) -> Result<HttpResponse> {
                 bits_allocated,
                 bits_stored,
                 photometric_interpretation,
+                number_of_frames,
             }))
         }
         Err(e) => Ok(HttpResponse::BadRequest().json(format!("Error reading DICOM: {}", e))),
     }
 }
 
-async fn get_dicom_image(path: web::Path<String>) -> Result<HttpResponse> {
-    let base_path = PathBuf::from("/home/gacquewi/dicom");
-    let file_path = base_path.join(path.into_inner());
+/// Decodes `file_path` and slices out the raw bytes of frame `frame_index`
+/// (0-based). Used by the WADO-RS frame endpoint, which addresses frames
+/// 1-based per the standard.
+pub(crate) fn read_frame_bytes(file_path: &Path, frame_index: u32) -> std::result::Result<Vec<u8>, String> {
+    let obj = open_file(file_path).map_err(|e| format!("Error reading DICOM: {}", e))?;
 
-    if !file_path.starts_with(&base_path) {
-        return Ok(HttpResponse::Forbidden().json("Access denied"));
+    let rows = obj.element_by_name("Rows").ok().and_then(|e| e.to_int::<u32>().ok()).unwrap_or(0);
+    let cols = obj.element_by_name("Columns").ok().and_then(|e| e.to_int::<u32>().ok()).unwrap_or(0);
+    let bits_allocated = obj.element_by_name("BitsAllocated").ok().and_then(|e| e.to_int::<u32>().ok()).unwrap_or(16);
+    let samples_per_pixel = obj.element_by_name("SamplesPerPixel").ok().and_then(|e| e.to_int::<u32>().ok()).unwrap_or(1);
+    let bytes_per_sample = if bits_allocated <= 8 { 1 } else { 2 };
+    let frame_size = (rows * cols * samples_per_pixel * bytes_per_sample) as usize;
+
+    let decoded = obj.decode_pixel_data().map_err(|e| format!("Error decoding pixel data: {}", e))?;
+    let data = decoded.data();
+
+    let start = frame_index as usize * frame_size;
+    let end = start + frame_size;
+    if end > data.len() {
+        return Err(format!("Frame {} out of range for {} byte pixel data", frame_index, data.len()));
     }
 
-    match open_file(&file_path) {
-        Ok(obj) => {
-            let rows = obj.element_by_name("Rows")
-                .ok()
-                .and_then(|e| e.to_int::<u32>().ok())
-                .unwrap_or(0);
-            
-            let cols = obj.element_by_name("Columns")
-                .ok()
-                .and_then(|e| e.to_int::<u32>().ok())
-                .unwrap_or(0);
-            
-            let bits_allocated = obj.element_by_name("BitsAllocated")
-                .ok()
-                .and_then(|e| e.to_int::<u32>().ok())
-                .unwrap_or(16);
-            
-            let photometric = obj.element_by_name("PhotometricInterpretation")
-                .ok()
-                .and_then(|e| e.to_str().ok())
-                .map(|s| s.to_string())
-                .unwrap_or_else(|| "MONOCHROME2".to_string());
-            
-            let samples_per_pixel = obj.element_by_name("SamplesPerPixel")
-                .ok()
-                .and_then(|e| e.to_int::<u32>().ok())
-                .unwrap_or(1);
+    Ok(data[start..end].to_vec())
+}
 
-            match obj.decode_pixel_data() {
-                Ok(decoded) => {
-                    let data = decoded.data();
-                    let total_pixels = (rows * cols) as usize;
-                    
-                    let normalized_data: Vec<u8> = if photometric.contains("RGB") || samples_per_pixel == 3 {
-                        // Image RGB - copier directement les données
-                        let expected_size = total_pixels * 3;
-                        if data.len() >= expected_size {
-                            data[..expected_size].to_vec()
-                        } else {
-                            eprintln!("Warning: RGB data size mismatch. Expected {}, got {}", expected_size, data.len());
-                            let mut result = vec![0u8; expected_size];
-                            let copy_len = data.len().min(expected_size);
-                            result[..copy_len].copy_from_slice(&data[..copy_len]);
-                            result
-                        }
-                    } else {
-                        // Image en niveau de gris (MONOCHROME)
-                        let bytes_per_pixel = if bits_allocated <= 8 { 1 } else { 2 };
-                        let expected_size = total_pixels * bytes_per_pixel;
-                        
-                        let mut pixel_values: Vec<u16> = Vec::new();
-                        
-                        if bytes_per_pixel == 1 {
-                            for &byte in data.iter().take(expected_size) {
-                                pixel_values.push(byte as u16);
-                            }
-                        } else {
-                            for i in (0..data.len().min(expected_size)).step_by(2) {
-                                if i + 1 < data.len() {
-                                    let value = u16::from_le_bytes([data[i], data[i + 1]]);
-                                    pixel_values.push(value);
-                                }
-                            }
-                        }
-                        
-                        if pixel_values.len() != total_pixels {
-                            eprintln!("Warning: pixel count mismatch. Expected {}, got {}", total_pixels, pixel_values.len());
-                        }
-                        
-                        let min_val = *pixel_values.iter().min().unwrap_or(&0) as f32;
-                        let max_val = *pixel_values.iter().max().unwrap_or(&1) as f32;
-                        let range = max_val - min_val;
-
-                        // Convertir en RGB (3 octets par pixel pour uniformité)
-                        let mut result = Vec::with_capacity(total_pixels * 3);
-                        for &pixel in &pixel_values {
-                            let gray = if range > 0.0 {
-                                ((pixel as f32 - min_val) / range * 255.0) as u8
+/// Encodes an RGB pixel buffer as a compressed `image/png` or `image/jpeg`
+/// response instead of shipping raw samples inside JSON, which bloats a
+/// 512x512 frame into megabytes of text.
+fn encode_rendered_image(width: u32, height: u32, rgb_data: &[u8], format: &str) -> Result<HttpResponse> {
+    let image = match RgbImage::from_raw(width, height, rgb_data.to_vec()) {
+        Some(img) => img,
+        None => return Ok(HttpResponse::BadRequest().json("Pixel buffer does not match image dimensions")),
+    };
+
+    let (image_format, content_type) = match format {
+        "jpeg" | "jpg" => (ImageFormat::Jpeg, "image/jpeg"),
+        _ => (ImageFormat::Png, "image/png"),
+    };
+
+    let mut bytes = Cursor::new(Vec::new());
+    if let Err(e) = image.write_to(&mut bytes, image_format) {
+        return Ok(HttpResponse::InternalServerError().json(format!("Error encoding image: {}", e)));
+    }
+
+    Ok(HttpResponse::Ok().content_type(content_type).body(bytes.into_inner()))
+}
+
+/// Applies the modality LUT + VOI windowing pipeline to frame
+/// `frame_index` (0-based) of an already-opened DICOM object and builds
+/// the HTTP response (raw JSON pixel data, or an encoded PNG/JPEG when
+/// `?format=` is set).
+fn render_dicom_frame(obj: &dicom::object::InMemDicomObject, frame_index: u32, query: &WindowQuery) -> Result<HttpResponse> {
+    let rows = obj.element_by_name("Rows")
+        .ok()
+        .and_then(|e| e.to_int::<u32>().ok())
+        .unwrap_or(0);
+
+    let cols = obj.element_by_name("Columns")
+        .ok()
+        .and_then(|e| e.to_int::<u32>().ok())
+        .unwrap_or(0);
+
+    let bits_allocated = obj.element_by_name("BitsAllocated")
+        .ok()
+        .and_then(|e| e.to_int::<u32>().ok())
+        .unwrap_or(16);
+
+    let photometric = obj.element_by_name("PhotometricInterpretation")
+        .ok()
+        .and_then(|e| e.to_str().ok())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "MONOCHROME2".to_string());
+
+    let samples_per_pixel = obj.element_by_name("SamplesPerPixel")
+        .ok()
+        .and_then(|e| e.to_int::<u32>().ok())
+        .unwrap_or(1);
+
+    match obj.decode_pixel_data() {
+        Ok(decoded) => {
+            let data = decoded.data();
+            let total_pixels = (rows * cols) as usize;
+
+            let normalized_data: Vec<u8> = if photometric.contains("RGB") || samples_per_pixel == 3 {
+                // Image RGB - copier directement les données
+                let frame_size = total_pixels * 3;
+                let frame_start = frame_index as usize * frame_size;
+                let frame_end = frame_start + frame_size;
+                if data.len() >= frame_end {
+                    data[frame_start..frame_end].to_vec()
+                } else {
+                    return Ok(HttpResponse::BadRequest().json(format!(
+                        "Frame {} out of range for {} byte pixel data", frame_index, data.len()
+                    )));
+                }
+            } else {
+                // Image en niveau de gris (MONOCHROME)
+                let bytes_per_pixel = if bits_allocated <= 8 { 1 } else { 2 };
+                let frame_size = total_pixels * bytes_per_pixel;
+                let frame_start = frame_index as usize * frame_size;
+                let frame_end = frame_start + frame_size;
+                if data.len() < frame_end {
+                    return Ok(HttpResponse::BadRequest().json(format!(
+                        "Frame {} out of range for {} byte pixel data", frame_index, data.len()
+                    )));
+                }
+                let frame_data = &data[frame_start..frame_end];
+
+                let signed = obj.element_by_name("PixelRepresentation")
+                    .ok()
+                    .and_then(|e| e.to_int::<u16>().ok())
+                    .unwrap_or(0) == 1;
+
+                let mut stored_values: Vec<i32> = Vec::new();
+
+                if bytes_per_pixel == 1 {
+                    for &byte in frame_data {
+                        stored_values.push(if signed { byte as i8 as i32 } else { byte as i32 });
+                    }
+                } else {
+                    for i in (0..frame_data.len()).step_by(2) {
+                        if i + 1 < frame_data.len() {
+                            let raw = [frame_data[i], frame_data[i + 1]];
+                            stored_values.push(if signed {
+                                i16::from_le_bytes(raw) as i32
                             } else {
-                                0
-                            };
-                            result.push(gray);
-                            result.push(gray);
-                            result.push(gray);
+                                u16::from_le_bytes(raw) as i32
+                            });
                         }
-                        result
-                    };
-
-                    Ok(HttpResponse::Ok().json(DicomPixelData {
-                        width: cols,
-                        height: rows,
-                        data: normalized_data,
-                    }))
+                    }
+                }
+
+                if stored_values.len() != total_pixels {
+                    eprintln!("Warning: pixel count mismatch. Expected {}, got {}", total_pixels, stored_values.len());
+                }
+
+                // Modality LUT: stored pixel -> real-world value (e.g. Hounsfield units).
+                let rescale_slope = obj.element_by_name("RescaleSlope")
+                    .ok()
+                    .and_then(|e| e.to_str().ok())
+                    .and_then(|s| parse_first_f64(&s))
+                    .unwrap_or(1.0);
+                let rescale_intercept = obj.element_by_name("RescaleIntercept")
+                    .ok()
+                    .and_then(|e| e.to_str().ok())
+                    .and_then(|s| parse_first_f64(&s))
+                    .unwrap_or(0.0);
+
+                let values: Vec<f64> = stored_values.iter()
+                    .map(|&v| v as f64 * rescale_slope + rescale_intercept)
+                    .collect();
+
+                // VOI window: explicit ?wc=&ww= wins, then the file's own
+                // WindowCenter/WindowWidth, falling back to a min/max auto-window.
+                let file_wc = obj.element_by_name("WindowCenter")
+                    .ok()
+                    .and_then(|e| e.to_str().ok())
+                    .and_then(|s| parse_first_f64(&s));
+                let file_ww = obj.element_by_name("WindowWidth")
+                    .ok()
+                    .and_then(|e| e.to_str().ok())
+                    .and_then(|s| parse_first_f64(&s));
+
+                // wc and ww are resolved independently so a caller driving
+                // interactive window/level with e.g. only `?wc=` still gets
+                // their value honored, paired with a sensible width instead
+                // of falling back to a full auto-window for both.
+                let min_val = values.iter().cloned().fold(f64::INFINITY, f64::min);
+                let max_val = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                let auto_center = (max_val + min_val) / 2.0;
+                let auto_width = (max_val - min_val).max(1.0);
+
+                let window_center = query.wc.or(file_wc).unwrap_or(auto_center);
+                let window_width = query.ww.or(file_ww).filter(|&ww| ww > 0.0).unwrap_or(auto_width);
+
+                let invert = photometric.contains("MONOCHROME1");
+
+                // Convertir en RGB (3 octets par pixel pour uniformité)
+                let mut result = Vec::with_capacity(total_pixels * 3);
+                for &value in &values {
+                    let gray = apply_voi_window(value, window_center, window_width, invert);
+                    result.push(gray);
+                    result.push(gray);
+                    result.push(gray);
                 }
-                Err(e) => Ok(HttpResponse::BadRequest().json(format!("Error decoding pixel data: {}", e))),
+                result
+            };
+
+            // Raw JSON pixel data (consumed by the wasm_bindgen
+            // `render_dicom_image` path) unless the client asked for a
+            // rendered, compressed frame via `?format=`.
+            match query.format.as_deref() {
+                Some(fmt) => encode_rendered_image(cols, rows, &normalized_data, fmt),
+                None => Ok(HttpResponse::Ok().json(DicomPixelData {
+                    width: cols,
+                    height: rows,
+                    data: normalized_data,
+                })),
             }
         }
+        Err(e) => Ok(HttpResponse::BadRequest().json(format!("Error decoding pixel data: {}", e))),
+    }
+}
+
+async fn get_dicom_image(path: web::Path<String>, query: web::Query<WindowQuery>) -> Result<HttpResponse> {
+    let base_path = PathBuf::from("/home/gacquewi/dicom");
+    let file_path = base_path.join(path.into_inner());
+
+    if !file_path.starts_with(&base_path) {
+        return Ok(HttpResponse::Forbidden().json("Access denied"));
+    }
+
+    match open_file(&file_path) {
+        Ok(obj) => render_dicom_frame(&obj, 0, &query),
         Err(e) => Ok(HttpResponse::BadRequest().json(format!("Error reading DICOM: {}", e))),
     }
 }
 
+/// `GET /api/dicom/image/{path}/frames/{n}` - same pipeline as
+/// [`get_dicom_image`], but for one frame (0-based) of a multi-frame
+/// instance (enhanced CT/MR, ultrasound) instead of assuming a single
+/// `Rows`x`Columns` frame per file.
+async fn get_dicom_image_frame(path: web::Path<(String, u32)>, query: web::Query<WindowQuery>) -> Result<HttpResponse> {
+    let (relative_path, frame_index) = path.into_inner();
+    let base_path = PathBuf::from("/home/gacquewi/dicom");
+    let file_path = base_path.join(relative_path);
+
+    if !file_path.starts_with(&base_path) {
+        return Ok(HttpResponse::Forbidden().json("Access denied"));
+    }
+
+    match open_file(&file_path) {
+        Ok(obj) => render_dicom_frame(&obj, frame_index, &query),
+        Err(e) => Ok(HttpResponse::BadRequest().json(format!("Error reading DICOM: {}", e))),
+    }
+}
+
+#[derive(Serialize)]
+struct SeriesFrameEntry {
+    path: String,
+    instance_number: Option<i64>,
+    number_of_frames: u32,
+}
+
+#[derive(Serialize)]
+struct SeriesFramesResponse {
+    series_uid: String,
+    total_frames: u32,
+    frames: Vec<SeriesFrameEntry>,
+}
+
+/// `GET /api/dicom/series/{series_uid}/frames` - an ordered frame list
+/// for a whole series, so the frontend can scroll/cine through a volume
+/// spread across several multi-frame instances.
+async fn get_series_frames(
+    state: web::Data<dicom_index::IndexState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let series_uid = path.into_inner();
+    let base_path = PathBuf::from("/home/gacquewi/dicom");
+
+    let images = {
+        let index = state.0.read().unwrap();
+        dicom_index::images_for_series(&index, &series_uid)
+    };
+
+    let Some(images) = images else {
+        return Ok(HttpResponse::NotFound().json("Series not found"));
+    };
+
+    let mut ordered: Vec<(SeriesFrameEntry, Option<f64>)> = Vec::new();
+    for relative_path in images {
+        let file_path = base_path.join(&relative_path);
+        let Ok(obj) = open_file(&file_path) else { continue };
+
+        let instance_number = obj.element_by_name("InstanceNumber")
+            .ok()
+            .and_then(|e| e.to_int::<i64>().ok());
+
+        let position_z = obj.element_by_name("ImagePositionPatient")
+            .ok()
+            .and_then(|e| e.to_str().ok())
+            .and_then(|s| s.split('\\').nth(2).and_then(|z| z.trim().parse::<f64>().ok()));
+
+        let number_of_frames = obj.element_by_name("NumberOfFrames")
+            .ok()
+            .and_then(|e| e.to_int::<u32>().ok())
+            .unwrap_or(1);
+
+        ordered.push((SeriesFrameEntry { path: relative_path, instance_number, number_of_frames }, position_z));
+    }
+
+    // Sort by InstanceNumber when every instance has one, otherwise fall
+    // back to the z-component of ImagePositionPatient. This has to be
+    // decided once for the whole series, not per compared pair - mixing
+    // the two criteria within a single sort is not a transitive order.
+    let use_instance_number = ordered.iter().all(|(entry, _)| entry.instance_number.is_some());
+    ordered.sort_by(|(a, a_pos), (b, b_pos)| {
+        if use_instance_number {
+            a.instance_number.cmp(&b.instance_number)
+        } else {
+            a_pos.partial_cmp(b_pos).unwrap_or(std::cmp::Ordering::Equal)
+        }
+    });
+
+    let frames: Vec<SeriesFrameEntry> = ordered.into_iter().map(|(entry, _)| entry).collect();
+    let total_frames = frames.iter().map(|f| f.number_of_frames).sum();
+
+    Ok(HttpResponse::Ok().json(SeriesFramesResponse { series_uid, total_frames, frames }))
+}
+
 async fn list_root_files() -> Result<HttpResponse> {
     list_files(web::Path::from(String::new())).await
 }
@@ -398,20 +516,101 @@ async fn main() -> std::io::Result<()> {
     println!("Starting DICOM viewer server on http://localhost:8104");
     println!("Serving files from /home/gacquewi/dicom");
 
-    HttpServer::new(|| {
+    println!("Building DICOM metadata index...");
+    let index_state = web::Data::new(dicom_index::IndexState(std::sync::RwLock::new(dicom_index::load_or_build())));
+
+    HttpServer::new(move || {
         let cors = Cors::permissive();
-        
+
         App::new()
             .wrap(cors)
+            .app_data(index_state.clone())
             .route("/", web::get().to(index))
             .route("/api/tree", web::get().to(get_dicom_tree))
+            .route("/api/reindex", web::post().to(dicom_index::reindex))
+            .route("/api/search", web::get().to(dicom_index::search))
             .route("/api/files", web::get().to(list_root_files))
             .route("/api/files/{path:.*}", web::get().to(list_files))
             .route("/api/dicom/info/{path:.*}", web::get().to(get_dicom_info))
+            .route("/api/dicom/series/{series_uid}/frames", web::get().to(get_series_frames))
+            .route("/api/dicom/image/{path:.*}/frames/{n}", web::get().to(get_dicom_image_frame))
             .route("/api/dicom/image/{path:.*}", web::get().to(get_dicom_image))
+            // DICOMweb (QIDO-RS / WADO-RS), for off-the-shelf viewers.
+            .route("/studies", web::get().to(dicomweb::search_studies))
+            .route("/studies/{study}/series", web::get().to(dicomweb::search_series))
+            .route(
+                "/studies/{study}/series/{series}/instances",
+                web::get().to(dicomweb::search_instances),
+            )
+            .route(
+                "/studies/{study}/series/{series}/instances/{instance}/metadata",
+                web::get().to(dicomweb::retrieve_metadata),
+            )
+            .route(
+                "/studies/{study}/series/{series}/instances/{instance}/frames/{n}",
+                web::get().to(dicomweb::retrieve_frame),
+            )
             .service(fs::Files::new("/static", "./static"))
     })
     .bind(("0.0.0.0", 8104))?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_first_f64_single_value() {
+        assert_eq!(parse_first_f64("40"), Some(40.0));
+        assert_eq!(parse_first_f64(" -0.5 "), Some(-0.5));
+    }
+
+    #[test]
+    fn parse_first_f64_takes_first_of_multivalued() {
+        assert_eq!(parse_first_f64("40\\400"), Some(40.0));
+    }
+
+    #[test]
+    fn parse_first_f64_rejects_garbage() {
+        assert_eq!(parse_first_f64(""), None);
+        assert_eq!(parse_first_f64("not a number"), None);
+    }
+
+    #[test]
+    fn voi_window_midpoint_is_mid_gray() {
+        assert_eq!(apply_voi_window(100.0, 100.0, 200.0, false), 128);
+    }
+
+    #[test]
+    fn voi_window_clamps_outside_range() {
+        assert_eq!(apply_voi_window(-1000.0, 40.0, 400.0, false), 0);
+        assert_eq!(apply_voi_window(1000.0, 40.0, 400.0, false), 255);
+    }
+
+    #[test]
+    fn voi_window_degenerate_width_is_a_hard_threshold() {
+        // width <= 1.0 is a guard against the division in the normal
+        // branch, not just an edge case of it.
+        assert_eq!(apply_voi_window(39.0, 40.0, 1.0, false), 0);
+        assert_eq!(apply_voi_window(40.0, 40.0, 1.0, false), 255);
+        assert_eq!(apply_voi_window(40.0, 40.0, 0.0, false), 255);
+    }
+
+    #[test]
+    fn voi_window_handles_signed_rescaled_values() {
+        // A typical CT soft-tissue window over Hounsfield units, which are
+        // negative below water (0 HU) - this only works if the pixel
+        // pipeline treated the stored values as signed before rescaling.
+        assert_eq!(apply_voi_window(-1000.0, 40.0, 400.0, false), 0);
+        assert_eq!(apply_voi_window(40.0, 40.0, 400.0, false), 128);
+    }
+
+    #[test]
+    fn voi_window_monochrome1_inverts() {
+        let normal = apply_voi_window(40.0, 40.0, 400.0, false);
+        let inverted = apply_voi_window(40.0, 40.0, 400.0, true);
+        assert_eq!(inverted, 255 - normal);
+    }
+}